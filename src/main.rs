@@ -1,5 +1,6 @@
 use clap::Parser;
 use md5::{Digest, Md5};
+use rayon::prelude::*;
 use relative_path::{RelativePathBuf, RelativePath};
 use std::{
     collections::{BTreeSet, HashMap, VecDeque},
@@ -14,6 +15,112 @@ enum ExistingFileAction {
     Update,
 }
 
+/// Content-hashing algorithm used to fingerprint files
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Eq, PartialEq)]
+enum HashType {
+    Md5,
+    Blake3,
+    Crc32,
+    Xxh3,
+}
+
+impl HashType {
+    /// The token stored in the database header line (`#hash <token>`)
+    fn as_str(&self) -> &'static str {
+        match self {
+            HashType::Md5 => "md5",
+            HashType::Blake3 => "blake3",
+            HashType::Crc32 => "crc32",
+            HashType::Xxh3 => "xxh3",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "md5" => Some(HashType::Md5),
+            "blake3" => Some(HashType::Blake3),
+            "crc32" => Some(HashType::Crc32),
+            "xxh3" => Some(HashType::Xxh3),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for HashType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Streaming digest backend, boxed so `compute_hash` can dispatch to whichever
+/// algorithm was selected on the command line without changing its read loop
+trait HashBackend {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> String;
+}
+
+struct Md5Backend(Md5);
+
+impl HashBackend for Md5Backend {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|v| format!("{v:02x}"))
+            .reduce(|a, b| format!("{a}{b}"))
+            .unwrap()
+    }
+}
+
+struct Blake3Backend(blake3::Hasher);
+
+impl HashBackend for Blake3Backend {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        self.0.finalize().to_hex().to_string()
+    }
+}
+
+struct Crc32Backend(crc32fast::Hasher);
+
+impl HashBackend for Crc32Backend {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:08x}", self.0.finalize())
+    }
+}
+
+struct Xxh3Backend(xxhash_rust::xxh3::Xxh3);
+
+impl HashBackend for Xxh3Backend {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:016x}", self.0.digest())
+    }
+}
+
+fn new_hash_backend(hash_type: HashType) -> Box<dyn HashBackend> {
+    match hash_type {
+        HashType::Md5 => Box::new(Md5Backend(Md5::new())),
+        HashType::Blake3 => Box::new(Blake3Backend(blake3::Hasher::new())),
+        HashType::Crc32 => Box::new(Crc32Backend(crc32fast::Hasher::new())),
+        HashType::Xxh3 => Box::new(Xxh3Backend(xxhash_rust::xxh3::Xxh3::new())),
+    }
+}
+
 /// File Indexing Program
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -41,6 +148,46 @@ struct Args {
     /// Determines how many threads run at the same time
     #[clap(short, long, value_parser, default_value_t = 0)]
     processes: usize,
+
+    /// Hash algorithm used to fingerprint file contents
+    #[clap(long, value_enum, default_value_t = HashType::Md5)]
+    hash: HashType,
+
+    /// After the initial pass, keep running and update the database as files change
+    #[clap(long, value_parser, default_value_t = false)]
+    watch: bool,
+
+    /// Report groups of files with identical content instead of updating the database
+    #[clap(long, value_parser, default_value_t = false)]
+    find_duplicates: bool,
+
+    /// With --find-duplicates, emit machine-readable JSON records instead of plain text
+    #[clap(long, value_parser, default_value_t = false)]
+    duplicates_json: bool,
+
+    /// Store a MIME-type column alongside each file's digest
+    #[clap(long, value_parser, default_value_t = false)]
+    mime: bool,
+
+    /// Classify MIME types by sniffing file contents, not just the extension
+    #[clap(long, value_parser, default_value_t = false)]
+    mime_sniff: bool,
+
+    /// Only index files whose MIME type matches one of these comma-separated
+    /// patterns (e.g. `image/*,video/*`)
+    #[clap(long, value_delimiter = ',')]
+    only_types: Vec<String>,
+
+    /// Output format for the end-of-run scan summary
+    #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+    report: ReportFormat,
+}
+
+/// How the end-of-run `ScanReport` is presented
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Eq, PartialEq)]
+enum ReportFormat {
+    Text,
+    Json,
 }
 
 fn find_files_in_directory(p: &Path, args: &Args) -> Vec<PathBuf> {
@@ -53,27 +200,94 @@ fn find_files_in_directory(p: &Path, args: &Args) -> Vec<PathBuf> {
     for entry in std::fs::read_dir(p).unwrap() {
         let entry = entry.unwrap();
         let path = entry.path();
+
         if path.is_dir() {
             files.extend(find_files_in_directory(&path, args));
-        } else if path.file_name().unwrap().to_str().unwrap().starts_with('.')
-            && !args.include_dot_files
-        {
+            continue;
+        }
+
+        if path.file_name().unwrap().to_str().unwrap().starts_with('.') && !args.include_dot_files {
             // Skip dot files
             continue;
-        } else {
-            files.push(path);
         }
+
+        if !args.only_types.is_empty() && !matches_only_types(&path, args) {
+            continue;
+        }
+
+        files.push(path);
     }
 
     files
 }
 
-fn compute_hash(p: &Path) -> String {
+/// Whether `path`'s MIME type matches one of `args.only_types`'s comma-separated
+/// patterns (a trailing `/*` matches the whole top-level type)
+fn matches_only_types(path: &Path, args: &Args) -> bool {
+    let leading_bytes = if args.mime_sniff {
+        read_leading_bytes(path, MIME_SNIFF_LEN)
+    } else {
+        Vec::new()
+    };
+
+    classify_mime(path, &leading_bytes, args.mime_sniff)
+        .map(|mime_type| {
+            args.only_types
+                .iter()
+                .any(|pattern| matches_type_pattern(&mime_type, pattern))
+        })
+        .unwrap_or(false)
+}
+
+fn matches_type_pattern(mime_type: &str, pattern: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime_type.split('/').next() == Some(top_level),
+        None => mime_type == pattern,
+    }
+}
+
+/// Bytes read from the start of a file for magic-number sniffing; large
+/// enough for every signature the `infer` crate recognizes
+const MIME_SNIFF_LEN: usize = 8192;
+
+fn read_leading_bytes(path: &Path, len: usize) -> Vec<u8> {
+    let Ok(mut f) = std::fs::File::open(path) else {
+        return Vec::new();
+    };
+
+    let mut buf = vec![0u8; len];
+    let read_len = f.read(&mut buf).unwrap_or(0);
+    buf.truncate(read_len);
+    buf
+}
+
+/// Best-effort MIME type for a file: magic-number sniffing of `leading_bytes`
+/// when `sniff` is set (catches misnamed or extensionless files), falling
+/// back to an extension-based guess
+fn classify_mime(path: &Path, leading_bytes: &[u8], sniff: bool) -> Option<String> {
+    if sniff {
+        if let Some(kind) = infer::get(leading_bytes) {
+            return Some(kind.mime_type().to_string());
+        }
+    }
+
+    mime_guess::from_path(path).first().map(|m| m.essence_str().to_string())
+}
+
+fn compute_hash(p: &Path, hash_type: HashType) -> String {
+    compute_hash_and_leading_bytes(p, hash_type, 0).0
+}
+
+/// Streams `p` through the hash backend while also capturing up to
+/// `leading_len` bytes from the start, so MIME sniffing can reuse this read
+/// instead of opening the file a second time. Pass `leading_len: 0` to skip.
+fn compute_hash_and_leading_bytes(p: &Path, hash_type: HashType, leading_len: usize) -> (String, Vec<u8>) {
     let mut f = std::fs::File::open(p).unwrap();
 
     let mut buf = [0u8; 81920];
 
-    let mut hasher = Md5::new();
+    let mut hasher = new_hash_backend(hash_type);
+    let mut leading_bytes = Vec::with_capacity(leading_len);
 
     loop {
         let read_len = f.read(&mut buf).unwrap();
@@ -81,60 +295,201 @@ fn compute_hash(p: &Path) -> String {
             break;
         }
 
+        if leading_bytes.len() < leading_len {
+            let take = (leading_len - leading_bytes.len()).min(read_len);
+            leading_bytes.extend_from_slice(&buf[..take]);
+        }
+
         hasher.update(&buf[..read_len]);
     }
 
-    hasher
-        .finalize()
-        .iter()
-        .map(|v| format!("{v:02x}"))
-        .reduce(|a, b| format!("{a}{b}"))
+    (hasher.finalize(), leading_bytes)
+}
+
+/// A cached digest plus the file metadata it was computed from, so unchanged
+/// files can be recognized without rereading their contents
+#[derive(Clone)]
+struct FileRecord {
+    hash: String,
+    size: Option<u64>,
+    mtime: Option<u64>,
+    mime: Option<String>,
+}
+
+/// Nanosecond resolution matters here: two edits that land in the same wall-clock
+/// second (scripted regeneration, CI artifacts, fast successive writes) would
+/// otherwise share a mtime and defeat the fast-skip/change-detection check below
+fn file_mtime_nanos(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
         .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
 }
 
 struct FileDatabase {
-    files: HashMap<RelativePathBuf, String>,
+    files: HashMap<RelativePathBuf, FileRecord>,
+    hash_type: HashType,
+    /// Whether the `mime` column is written on save; set from `--mime` for a
+    /// fresh database, or when loading one, from the `#mime` header or `--mime`
+    mime_enabled: bool,
     change_count: usize,
 }
 
 impl FileDatabase {
-    pub fn new() -> Self {
+    pub fn new(hash_type: HashType, mime_enabled: bool) -> Self {
         Self {
             files: HashMap::new(),
+            hash_type,
+            mime_enabled,
             change_count: 0,
         }
     }
 
-    pub fn load(path: &Path) -> Self {
+    /// Loads a database file, refusing to proceed if the digests it stores
+    /// were produced by a different algorithm than `hash_type` requests,
+    /// since the two are not comparable to each other
+    pub fn load(path: &Path, hash_type: HashType, mime_requested: bool) -> Self {
+        let contents = std::fs::read_to_string(path).unwrap();
+        let mut lines = contents.lines();
+
+        let stored_hash_type = match lines.clone().next().and_then(|l| l.strip_prefix("#hash ")) {
+            Some(token) => {
+                lines.next();
+                HashType::from_str(token.trim())
+                    .unwrap_or_else(|| panic!("unknown hash algorithm '{token}' in database header"))
+            }
+            // Older databases predate the header line and were always MD5
+            None => HashType::Md5,
+        };
+
+        if stored_hash_type != hash_type {
+            panic!(
+                "database {} was built with {} hashes but {} was requested; pick a matching --hash or rebuild the database",
+                path.to_str().unwrap(),
+                stored_hash_type,
+                hash_type
+            );
+        }
+
+        // Older databases predate the MIME column entirely
+        let stored_mime_enabled = match lines.clone().next() {
+            Some(l) if l.trim() == "#mime" => {
+                lines.next();
+                true
+            }
+            _ => false,
+        };
+
         Self {
-            files: std::fs::read_to_string(path)
-                .unwrap()
-                .lines()
-                .filter_map(|s| s.trim().split_once(' '))
-                .map(|(h, d)| (RelativePathBuf::from(d), h.to_string()))
+            files: lines
+                .filter_map(|s| FileDatabase::parse_line(s.trim(), stored_mime_enabled))
                 .collect::<HashMap<_, _>>(),
+            hash_type,
+            // `--mime` on a database that predates the column starts writing it from here on
+            mime_enabled: stored_mime_enabled || mime_requested,
             change_count: 0,
         }
     }
 
-    pub fn add_file(&mut self, f: &RelativePath, hash: &str) {
-        self.files.insert(f.to_owned(), hash.to_owned());
+    /// Parses a `hash size mtime [mime] path` line, falling back to the older
+    /// `hash path` format (no size/mtime/mime) so older databases keep loading;
+    /// `-` marks a field as unknown in any format
+    fn parse_line(s: &str, mime_enabled: bool) -> Option<(RelativePathBuf, FileRecord)> {
+        if s.is_empty() {
+            return None;
+        }
+
+        let field_count = if mime_enabled { 5 } else { 4 };
+        let mut parts = s.splitn(field_count, ' ');
+        let hash = parts.next()?;
+        let rest = parts.collect::<Vec<_>>();
+
+        match rest.len() {
+            4 if mime_enabled => {
+                let size = rest[0].parse::<u64>().ok();
+                let mtime = rest[1].parse::<u64>().ok();
+                let mime = (rest[2] != "-").then(|| rest[2].to_string());
+                Some((
+                    RelativePathBuf::from(rest[3]),
+                    FileRecord { hash: hash.to_string(), size, mtime, mime },
+                ))
+            }
+            3 if !mime_enabled => {
+                let size = rest[0].parse::<u64>().ok();
+                let mtime = rest[1].parse::<u64>().ok();
+                Some((
+                    RelativePathBuf::from(rest[2]),
+                    FileRecord { hash: hash.to_string(), size, mtime, mime: None },
+                ))
+            }
+            1 => Some((
+                RelativePathBuf::from(rest[0]),
+                FileRecord { hash: hash.to_string(), size: None, mtime: None, mime: None },
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn add_file(&mut self, f: &RelativePath, hash: &str, size: u64, mtime: u64, mime: Option<String>) {
+        self.files.insert(
+            f.to_owned(),
+            FileRecord {
+                hash: hash.to_owned(),
+                size: Some(size),
+                mtime: Some(mtime),
+                mime,
+            },
+        );
         self.change_count += 1;
     }
 
-    pub fn get_hash(&self, f: &RelativePath) -> Option<&str> {
-        self.files.get(f).map(|s| s.as_ref())
+    /// Drops a file from the index, e.g. in response to a filesystem removal
+    /// event seen while watching. No-op if the file was never indexed.
+    pub fn remove_file(&mut self, f: &RelativePath) {
+        if self.files.remove(f).is_some() {
+            println!("Removing {f}");
+            self.change_count += 1;
+        }
+    }
+
+    pub fn get_record(&self, f: &RelativePath) -> Option<&FileRecord> {
+        self.files.get(f)
+    }
+
+    /// A read-only copy of the current records, handed to the rayon scan
+    /// pass so workers can check "already indexed?" without sharing a lock
+    pub fn snapshot(&self) -> HashMap<RelativePathBuf, FileRecord> {
+        self.files.clone()
     }
 
     pub fn save(&mut self, path: &Path) {
         let mut file_list = self.files.iter().collect::<Vec<_>>();
         file_list.sort_by(|a, b| a.0.cmp(b.0));
 
+        let headers = std::iter::once(format!("#hash {}", self.hash_type.as_str()))
+            .chain(self.mime_enabled.then(|| "#mime".to_string()));
+
+        fn field(v: Option<u64>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string())
+        }
+
+        let mime_enabled = self.mime_enabled;
+        fn mime_field(v: &Option<String>) -> &str {
+            v.as_deref().unwrap_or("-")
+        }
+
         std::fs::write(
             path,
-            file_list
-                .iter()
-                .map(|(p, h)| format!("{h} {p}"))
+            headers
+                .chain(file_list.iter().map(|(p, r)| {
+                    if mime_enabled {
+                        format!("{} {} {} {} {p}", r.hash, field(r.size), field(r.mtime), mime_field(&r.mime))
+                    } else {
+                        format!("{} {} {} {p}", r.hash, field(r.size), field(r.mtime))
+                    }
+                }))
                 .reduce(|a, b| format!("{a}\n{b}"))
                 .unwrap_or(String::new()),
         )
@@ -143,18 +498,22 @@ impl FileDatabase {
         self.change_count = 0;
     }
 
-    pub fn truncate_to_existing(&mut self, files: &BTreeSet<RelativePathBuf>) {
+    /// Drops every indexed file no longer present in `files`, returning the
+    /// removed paths so the caller can fold them into a `ScanReport`
+    pub fn truncate_to_existing(&mut self, files: &BTreeSet<RelativePathBuf>) -> Vec<RelativePathBuf> {
         let remove_files = self.files
             .keys()
             .filter(|k| !files.contains(*k))
             .cloned()
             .collect::<Vec<_>>();
 
-        for f in remove_files {
-            self.files.remove(&f);
+        for f in &remove_files {
+            self.files.remove(f);
             println!("Removing {f}");
             self.change_count += 1;
         }
+
+        remove_files
     }
 
     pub fn num_changes(&self) -> usize {
@@ -169,81 +528,463 @@ impl FileDatabase {
 #[derive(Clone)]
 struct ThreadArgs {
     base_path: PathBuf,
-    existing_action: ExistingFileAction,
     db_file: PathBuf,
-    fail_due_to_difference: Arc<Mutex<bool>>,
+    hash_type: HashType,
+    mime_enabled: bool,
+    mime_sniff: bool,
     file_db: Arc<Mutex<FileDatabase>>,
     input_queue: Arc<Mutex<VecDeque<RelativePathBuf>>>,
 }
 
 impl ThreadArgs {
-    pub fn new(args: &Args, base_path: &Path, files: VecDeque<RelativePathBuf>) -> Self {
-        let file_db = if args.db_file.exists() {
-            FileDatabase::load(&args.db_file)
-        } else {
-            FileDatabase::new()
-        };
-
+    /// Wraps an already-scanned `file_db` for the watch daemon, which keeps
+    /// applying small incremental updates after the bulk rayon pass finishes
+    pub fn new(args: &Args, base_path: &Path, file_db: FileDatabase) -> Self {
+        let mime_enabled = file_db.mime_enabled;
         Self {
             base_path: base_path.to_owned(),
-            existing_action: args.existing,
             db_file: args.db_file.to_owned(),
-            fail_due_to_difference: Arc::new(Mutex::new(false)),
+            hash_type: args.hash,
+            mime_enabled,
+            mime_sniff: args.mime_sniff,
             file_db: Arc::new(Mutex::new(file_db)),
-            input_queue: Arc::new(Mutex::new(files)),
+            input_queue: Arc::new(Mutex::new(VecDeque::new())),
         }
     }
 }
 
-fn process_file(args: &ThreadArgs, f: &RelativePath) {
-    let f_path = f.to_path(&args.base_path);
+/// What scanning a single file found, before anything has touched the
+/// database. Kept free of shared state so it can be produced by a rayon
+/// worker and folded into the database afterwards on a single thread.
+enum FileOutcome {
+    Unchanged,
+    Added(FileRecord),
+    MetadataRefreshed(FileRecord),
+    ContentChanged { record: FileRecord, old_hash: String },
+}
+
+/// Hashes `f` (when needed) against its previously known record and reports
+/// what changed. Does not touch the database - safe to call from any thread.
+fn scan_file(
+    base_path: &Path,
+    existing_record: Option<&FileRecord>,
+    existing_action: ExistingFileAction,
+    hash_type: HashType,
+    mime_enabled: bool,
+    mime_sniff: bool,
+    f: &RelativePath,
+) -> FileOutcome {
+    let f_path = f.to_path(base_path);
+
+    // Only pull sniff bytes out of the hashing read loop when they'll actually be used
+    let sniff_len = if mime_enabled && mime_sniff { MIME_SNIFF_LEN } else { 0 };
+    let classify = |leading_bytes: &[u8]| mime_enabled.then(|| classify_mime(&f_path, leading_bytes, mime_sniff)).flatten();
 
-    let existing_hash = args.file_db.lock().unwrap().get_hash(f).map(|s| s.to_owned());
-    let mut db_hash = None;
+    match existing_record {
+        Some(old_record) => {
+            if existing_action == ExistingFileAction::Nothing {
+                return FileOutcome::Unchanged;
+            }
+
+            let metadata = std::fs::metadata(&f_path).unwrap();
+            let size = metadata.len();
+            let mtime = file_mtime_nanos(&metadata);
+
+            // Missing size/mtime (older database) means "unknown", so it must be rehashed
+            let unchanged = old_record.size == Some(size) && old_record.mtime == Some(mtime);
+            if unchanged {
+                return FileOutcome::Unchanged;
+            }
 
-    if let Some(old_hash) = existing_hash {
-        if args.existing_action != ExistingFileAction::Nothing {
             println!("Computing {f}");
-            let new_hash = compute_hash(&f_path);
+            let (new_hash, leading_bytes) = compute_hash_and_leading_bytes(&f_path, hash_type, sniff_len);
+            let record = FileRecord {
+                hash: new_hash.clone(),
+                size: Some(size),
+                mtime: Some(mtime),
+                mime: classify(&leading_bytes),
+            };
 
-            if new_hash != old_hash {
-                println!("  Mismatch in hash for {f} => old {old_hash}, new {new_hash}");
+            if new_hash != old_record.hash {
+                println!("  Mismatch in hash for {f} => old {}, new {new_hash}", old_record.hash);
+                FileOutcome::ContentChanged { record, old_hash: old_record.hash.clone() }
+            } else {
+                // Metadata changed but content didn't; refresh the cache so the next run can fast-skip
+                FileOutcome::MetadataRefreshed(record)
+            }
+        }
+        None => {
+            println!("Starting {}", f_path.to_str().unwrap());
+            let metadata = std::fs::metadata(&f_path).unwrap();
+            let (hash, leading_bytes) = compute_hash_and_leading_bytes(&f_path, hash_type, sniff_len);
+            FileOutcome::Added(FileRecord {
+                hash,
+                size: Some(metadata.len()),
+                mtime: Some(file_mtime_nanos(&metadata)),
+                mime: classify(&leading_bytes),
+            })
+        }
+    }
+}
 
-                match args.existing_action {
-                    ExistingFileAction::Update => {
-                        db_hash = Some(("  Updating!", new_hash));
-                    }
-                    ExistingFileAction::Check => {
-                        *args.fail_due_to_difference.lock().unwrap() = true;
-                    }
-                    _ => panic!(),
-                }
+/// Folds one file's `FileOutcome` into `file_db`. Under `ExistingFileAction::Check`
+/// neither a content change nor a bare metadata refresh touches the database -
+/// `check` is a read-only integrity check, and the caller is expected to have
+/// already recorded any mismatch via `ScanReport::record`.
+fn apply_outcome(
+    file_db: &mut FileDatabase,
+    existing_action: ExistingFileAction,
+    f: &RelativePath,
+    outcome: FileOutcome,
+) {
+    let db_update = match outcome {
+        FileOutcome::Unchanged => None,
+        FileOutcome::Added(record) => Some(("Adding", record)),
+        FileOutcome::MetadataRefreshed(record) => match existing_action {
+            ExistingFileAction::Update => Some(("  Refreshed metadata for", record)),
+            ExistingFileAction::Check => None,
+            ExistingFileAction::Nothing => unreachable!("scan_file never hashes under Nothing"),
+        },
+        FileOutcome::ContentChanged { record, .. } => match existing_action {
+            ExistingFileAction::Update => Some(("  Updating!", record)),
+            ExistingFileAction::Check => None,
+            ExistingFileAction::Nothing => unreachable!("scan_file never hashes under Nothing"),
+        },
+    };
+
+    if let Some((add_str, record)) = db_update {
+        println!("{add_str} {f} - {}!", record.hash);
+        file_db.add_file(f, &record.hash, record.size.unwrap(), record.mtime.unwrap(), record.mime);
+    }
+}
+
+/// One entry of a `ScanReport`'s `hash_mismatches` bucket: the file plus the
+/// stored and newly computed digests that disagree
+#[derive(Debug, serde::Serialize)]
+struct HashMismatch {
+    path: String,
+    old_hash: String,
+    new_hash: String,
+}
+
+/// Accumulates categorized scan outcomes across a run so the summary can be
+/// printed (or serialized with `--report json`) once at the end instead of
+/// scattering detail across log lines
+#[derive(Debug, Default, serde::Serialize)]
+struct ScanReport {
+    added: Vec<String>,
+    updated: Vec<String>,
+    unchanged: Vec<String>,
+    missing_on_disk: Vec<String>,
+    hash_mismatches: Vec<HashMismatch>,
+}
+
+impl ScanReport {
+    /// Buckets a file's `scan_file` outcome; `existing_action` decides whether
+    /// a content change is an update (applied) or a mismatch (reported only)
+    fn record(&mut self, f: &RelativePath, outcome: &FileOutcome, existing_action: ExistingFileAction) {
+        match outcome {
+            FileOutcome::Unchanged => self.unchanged.push(f.to_string()),
+            FileOutcome::Added(_) => self.added.push(f.to_string()),
+            FileOutcome::MetadataRefreshed(_) => self.updated.push(f.to_string()),
+            FileOutcome::ContentChanged { record, old_hash } => match existing_action {
+                ExistingFileAction::Update => self.updated.push(f.to_string()),
+                ExistingFileAction::Check => self.hash_mismatches.push(HashMismatch {
+                    path: f.to_string(),
+                    old_hash: old_hash.clone(),
+                    new_hash: record.hash.clone(),
+                }),
+                ExistingFileAction::Nothing => unreachable!("scan_file never hashes under Nothing"),
+            },
+        }
+    }
+
+    fn record_missing(&mut self, f: &RelativePath) {
+        self.missing_on_disk.push(f.to_string());
+    }
+
+    fn print_summary(&self) {
+        println!("Scan summary:");
+        println!("  Added:           {}", self.added.len());
+        println!("  Updated:         {}", self.updated.len());
+        println!("  Unchanged:       {}", self.unchanged.len());
+        println!("  Missing on disk: {}", self.missing_on_disk.len());
+        println!("  Hash mismatches: {}", self.hash_mismatches.len());
+
+        if !self.hash_mismatches.is_empty() {
+            println!("Mismatched files:");
+            for mismatch in &self.hash_mismatches {
+                println!("  {} (old {}, new {})", mismatch.path, mismatch.old_hash, mismatch.new_hash);
             }
         }
-    }  else {
-        println!("Starting {}", f_path.to_str().unwrap());
-        db_hash = Some(("Adding", compute_hash(&f_path)));
     }
+}
+
+/// The watch daemon always treats a changed tracked file as an update,
+/// independent of the global `--existing` flag - `check`/`nothing` would mean
+/// the database never reflects live edits, defeating the point of `--watch`.
+fn process_file(args: &ThreadArgs, f: &RelativePath) {
+    let existing_action = ExistingFileAction::Update;
+
+    let existing_record = args.file_db.lock().unwrap().get_record(f).cloned();
+    let outcome = scan_file(
+        &args.base_path,
+        existing_record.as_ref(),
+        existing_action,
+        args.hash_type,
+        args.mime_enabled,
+        args.mime_sniff,
+        f,
+    );
 
     let mut file_db = args.file_db.lock().unwrap();
 
-    if let Some((add_str, hash)) = db_hash {
-        file_db.add_file(f, &hash);
-        println!("{add_str} {f} - {hash}!");
-    }
+    apply_outcome(&mut file_db, existing_action, f, outcome);
 
     if file_db.num_changes() > 10 {
         file_db.save(&args.db_file);
     }
 }
 
-fn main() {
-    let args = Args::parse();
+/// What to do with a path once it settles at the end of a debounce window
+enum WatchAction {
+    Changed,
+    Removed,
+}
+
+/// Folds a raw filesystem event into `pending`, keyed by relative path, so
+/// that several events for the same file inside the debounce window collapse
+/// into a single action (the last one observed wins)
+fn collect_watch_event(
+    pending: &mut HashMap<RelativePathBuf, WatchAction>,
+    event: notify::Event,
+    base_path: &Path,
+    args: &Args,
+) {
+    let removed = matches!(event.kind, notify::EventKind::Remove(_));
+
+    for path in event.paths {
+        if path.is_dir() {
+            continue;
+        }
+
+        let is_dot_file = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.starts_with('.'))
+            .unwrap_or(false);
+        if is_dot_file && !args.include_dot_files {
+            continue;
+        }
+
+        let Ok(rel_path) = path.strip_prefix(base_path) else {
+            continue;
+        };
+        let Ok(rel) = RelativePathBuf::from_path(rel_path) else {
+            continue;
+        };
+
+        // A rename reports both the old and new path under Modify(Name(_));
+        // whichever one no longer exists on disk is the one that was removed
+        let action = if removed || !path.exists() {
+            WatchAction::Removed
+        } else {
+            WatchAction::Changed
+        };
+
+        if matches!(action, WatchAction::Changed) && !args.only_types.is_empty() && !matches_only_types(&path, args) {
+            continue;
+        }
+
+        pending.insert(rel, action);
+    }
+}
+
+/// Keeps the database live after the initial pass by watching `args.folders`
+/// recursively and folding filesystem events through the existing worker
+/// queue. Runs until the process is interrupted (e.g. with Ctrl+C).
+fn run_watch(args: &Args, targs: &ThreadArgs, base_path: &Path) {
+    use notify::Watcher;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).unwrap();
+
+    for folder in args.folders.iter() {
+        watcher
+            .watch(&base_path.join(folder), notify::RecursiveMode::Recursive)
+            .unwrap();
+    }
+
+    println!("Watching for changes (Ctrl+C to stop)...");
+
+    let debounce = std::time::Duration::from_millis(250);
+
+    while let Ok(first) = rx.recv().map(Result::unwrap) {
+        let mut pending = HashMap::new();
+        collect_watch_event(&mut pending, first, base_path, args);
+
+        // Coalesce any further events that arrive within the debounce window
+        let deadline = std::time::Instant::now() + debounce;
+        while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match rx.recv_timeout(remaining) {
+                Ok(event) => collect_watch_event(&mut pending, event.unwrap(), base_path, args),
+                Err(_) => break,
+            }
+        }
+
+        for (path, action) in pending {
+            match action {
+                WatchAction::Removed => {
+                    targs.file_db.lock().unwrap().remove_file(&path);
+                }
+                WatchAction::Changed => {
+                    targs.input_queue.lock().unwrap().push_back(path);
+                }
+            }
+        }
+
+        while let Some(f) = targs.input_queue.lock().unwrap().pop_front() {
+            process_file(targs, &f);
+        }
+
+        let mut file_db = targs.file_db.lock().unwrap();
+        if file_db.has_changes() {
+            file_db.save(&args.db_file);
+        }
+    }
+}
+
+/// One set of files that share identical content, reported by `--find-duplicates`
+#[derive(Debug, serde::Serialize)]
+struct DuplicateGroup {
+    digest: String,
+    size: u64,
+    paths: Vec<String>,
+}
+
+/// Runs `scan` on rayon's default global pool when `processes == 0` (the
+/// `--processes` default), or on a scoped pool pinned to that many threads
+/// otherwise - shared by every rayon hot path so `--processes` means the same
+/// thing everywhere it's honored
+fn run_with_pool<T: Send>(processes: usize, scan: impl FnOnce() -> T + Send) -> T {
+    if processes == 0 {
+        scan()
+    } else {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(processes)
+            .build()
+            .unwrap()
+            .install(scan)
+    }
+}
+
+/// Buckets `files` by size so the expensive hashing stage only ever runs on
+/// files that share a size with at least one other file
+fn size_buckets(
+    base_path: &Path,
+    files: &BTreeSet<RelativePathBuf>,
+) -> HashMap<u64, Vec<RelativePathBuf>> {
+    let mut by_size: HashMap<u64, Vec<RelativePathBuf>> = HashMap::new();
+
+    for f in files {
+        let metadata = std::fs::metadata(f.to_path(base_path)).unwrap();
+        by_size.entry(metadata.len()).or_default().push(f.clone());
+    }
+
+    by_size
+}
+
+/// Two-stage duplicate search: group by size first (a unique size can never
+/// collide), then hash only within multi-file size buckets and group by
+/// digest. Hashes through `run_with_pool` so `--processes` still controls
+/// parallelism here.
+fn find_duplicate_groups(
+    args: &Args,
+    base_path: &Path,
+) -> Vec<DuplicateGroup> {
+    let base_path = base_path.to_owned();
 
     let mut files = BTreeSet::new();
+    for folder in args.folders.iter() {
+        for f in find_files_in_directory(&base_path.join(folder), args) {
+            let rel_path = f.strip_prefix(&base_path).unwrap().to_path_buf();
+            files.insert(RelativePathBuf::from_path(&rel_path).unwrap());
+        }
+    }
+
+    let by_size = size_buckets(&base_path, &files);
+
+    let candidates = by_size
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let hash_type = args.hash;
+
+    let scan = || {
+        candidates
+            .par_iter()
+            .map(|f| {
+                let f_path = f.to_path(&base_path);
+                let size = std::fs::metadata(&f_path).unwrap().len();
+                let hash = compute_hash(&f_path, hash_type);
+                (f.clone(), size, hash)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let results = run_with_pool(args.processes, scan);
+
+    let mut by_hash: HashMap<(u64, String), Vec<RelativePathBuf>> = HashMap::new();
+    for (f, size, hash) in results {
+        by_hash.entry((size, hash)).or_default().push(f);
+    }
+
+    let mut groups = by_hash
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, digest), mut paths)| {
+            paths.sort();
+            DuplicateGroup {
+                digest,
+                size,
+                paths: paths.iter().map(|p| p.to_string()).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    groups.sort_by(|a, b| a.paths.cmp(&b.paths));
+    groups
+}
+
+fn run_find_duplicates(args: &Args, base_path: &Path) {
+    let groups = find_duplicate_groups(args, base_path);
+
+    if args.duplicates_json {
+        println!("{}", serde_json::to_string(&groups).unwrap());
+    } else {
+        for group in &groups {
+            println!("Duplicate set ({} bytes, {}):", group.size, group.digest);
+            for path in &group.paths {
+                println!("  {path}");
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
 
     let base_path = std::env::current_dir().unwrap().canonicalize().unwrap();
 
+    if args.find_duplicates {
+        run_find_duplicates(&args, &base_path);
+        return;
+    }
+
+    let mut files = BTreeSet::new();
+
     println!("Parsing {}", base_path.to_str().unwrap());
 
     for folder in args.folders.iter() {
@@ -256,49 +997,170 @@ fn main() {
 
     let files = files;
 
-    let targs = ThreadArgs::new(&args, &base_path, files.iter().cloned().collect());
+    let mut file_db = if args.db_file.exists() {
+        FileDatabase::load(&args.db_file, args.hash, args.mime)
+    } else {
+        FileDatabase::new(args.hash, args.mime)
+    };
+
+    // Read-only snapshot so the rayon workers below can check "already indexed?"
+    // without sharing a lock with each other
+    let existing_files = file_db.snapshot();
 
     println!("Running with {} threads", args.processes);
 
-    if args.processes == 0 {
-        for f in files.iter() {
-            process_file(&targs, f);
-        }
-    } else {
-        let mut threads = Vec::new();
+    let scan = || {
+        files
+            .par_iter()
+            .map(|f| {
+                let outcome = scan_file(
+                    &base_path,
+                    existing_files.get(f),
+                    args.existing,
+                    args.hash,
+                    file_db.mime_enabled,
+                    args.mime_sniff,
+                    f,
+                );
+                (f.clone(), outcome)
+            })
+            .collect::<Vec<_>>()
+    };
 
-        for _ in 0..args.processes {
-            let largs = targs.clone();
-            let thread = std::thread::spawn(move || {
-                loop {
-                    let val = largs.input_queue.lock().unwrap().pop_front();
+    let mut results = run_with_pool(args.processes, scan);
 
-                    if let Some(f) = val {
-                        process_file(&largs, &f);
-                    } else {
-                        break;
-                    }
-                }
-            });
-            threads.push(thread);
-        }
+    // Deterministic fold order regardless of how the rayon scheduler interleaved work
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        for t in threads {
-            t.join().unwrap();
+    let mut report = ScanReport::default();
+
+    for (f, outcome) in results {
+        report.record(&f, &outcome, args.existing);
+        apply_outcome(&mut file_db, args.existing, &f, outcome);
+
+        if file_db.num_changes() > 10 {
+            file_db.save(&args.db_file);
         }
     }
 
-    let mut file_db = targs.file_db.lock().unwrap();
-
     if args.remove_old_entries {
-        file_db.truncate_to_existing(&files);
+        for f in file_db.truncate_to_existing(&files) {
+            report.record_missing(&f);
+        }
     }
 
     if file_db.has_changes() {
         file_db.save(&args.db_file);
     }
 
-    if *targs.fail_due_to_difference.lock().unwrap() {
+    match args.report {
+        ReportFormat::Text => report.print_summary(),
+        ReportFormat::Json => println!("{}", serde_json::to_string(&report).unwrap()),
+    }
+
+    if args.watch {
+        let targs = ThreadArgs::new(&args, &base_path, file_db);
+        run_watch(&args, &targs, &base_path);
+        return;
+    }
+
+    // Hash mismatches under --existing check take priority, since they mean the
+    // index itself is suspect; a clean removal pass still gets its own nonzero code
+    if !report.hash_mismatches.is_empty() {
         std::process::exit(1);
+    } else if args.remove_old_entries && !report.missing_on_disk.is_empty() {
+        std::process::exit(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_rejects_empty() {
+        assert!(FileDatabase::parse_line("", false).is_none());
+    }
+
+    #[test]
+    fn parse_line_full_format() {
+        let (path, record) = FileDatabase::parse_line("abc123 456 789 some/file.txt", false).unwrap();
+        assert_eq!(path, RelativePathBuf::from("some/file.txt"));
+        assert_eq!(record.hash, "abc123");
+        assert_eq!(record.size, Some(456));
+        assert_eq!(record.mtime, Some(789));
+        assert_eq!(record.mime, None);
+    }
+
+    #[test]
+    fn parse_line_legacy_hash_path_format() {
+        let (path, record) = FileDatabase::parse_line("abc123 some/file.txt", false).unwrap();
+        assert_eq!(path, RelativePathBuf::from("some/file.txt"));
+        assert_eq!(record.hash, "abc123");
+        assert_eq!(record.size, None);
+        assert_eq!(record.mtime, None);
+    }
+
+    #[test]
+    fn parse_line_with_mime_column() {
+        let (path, record) = FileDatabase::parse_line("abc123 456 789 text/plain some/file.txt", true).unwrap();
+        assert_eq!(path, RelativePathBuf::from("some/file.txt"));
+        assert_eq!(record.size, Some(456));
+        assert_eq!(record.mtime, Some(789));
+        assert_eq!(record.mime, Some("text/plain".to_string()));
+    }
+
+    #[test]
+    fn parse_line_unknown_mime_marker() {
+        let (_, record) = FileDatabase::parse_line("abc123 456 789 - some/file.txt", true).unwrap();
+        assert_eq!(record.mime, None);
+    }
+
+    #[test]
+    fn parse_line_rejects_wrong_field_count_for_mode() {
+        // A mime-less (4-field) line read back with mime_enabled doesn't match
+        // either arm of the fallback and should be treated as malformed, not guessed at
+        assert!(FileDatabase::parse_line("abc123 456 789 some/file.txt", true).is_none());
+    }
+
+    #[test]
+    fn scan_report_buckets_each_outcome() {
+        let mut report = ScanReport::default();
+
+        report.record(RelativePath::new("unchanged.txt"), &FileOutcome::Unchanged, ExistingFileAction::Update);
+        report.record(
+            RelativePath::new("added.txt"),
+            &FileOutcome::Added(FileRecord { hash: "h".to_string(), size: None, mtime: None, mime: None }),
+            ExistingFileAction::Update,
+        );
+        report.record(
+            RelativePath::new("refreshed.txt"),
+            &FileOutcome::MetadataRefreshed(FileRecord { hash: "h".to_string(), size: None, mtime: None, mime: None }),
+            ExistingFileAction::Update,
+        );
+        report.record(
+            RelativePath::new("updated.txt"),
+            &FileOutcome::ContentChanged {
+                record: FileRecord { hash: "new".to_string(), size: None, mtime: None, mime: None },
+                old_hash: "old".to_string(),
+            },
+            ExistingFileAction::Update,
+        );
+        report.record(
+            RelativePath::new("mismatched.txt"),
+            &FileOutcome::ContentChanged {
+                record: FileRecord { hash: "new".to_string(), size: None, mtime: None, mime: None },
+                old_hash: "old".to_string(),
+            },
+            ExistingFileAction::Check,
+        );
+
+        assert_eq!(report.added, vec!["added.txt".to_string()]);
+        assert_eq!(report.unchanged, vec!["unchanged.txt".to_string()]);
+        assert_eq!(report.updated, vec!["refreshed.txt".to_string(), "updated.txt".to_string()]);
+        assert_eq!(report.hash_mismatches.len(), 1);
+        assert_eq!(report.hash_mismatches[0].path, "mismatched.txt");
+        assert_eq!(report.hash_mismatches[0].old_hash, "old");
+        assert_eq!(report.hash_mismatches[0].new_hash, "new");
     }
 }